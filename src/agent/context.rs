@@ -0,0 +1,218 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use futures::future::try_join_all;
+use replicate_rs::predictions::Prediction;
+use walkdir::WalkDir;
+
+use super::message::{Message, Role};
+
+// How many lines each chunk covers, and how many of those lines are shared
+// with the previous chunk so a snippet isn't cut off mid-thought.
+const CHUNK_LINES: usize = 40;
+const CHUNK_OVERLAP: usize = 10;
+
+// Replicate model used to embed chunks and queries. Kept separate from
+// `CompletionModel` since embedding and chat completion models live on
+// different Replicate endpoints.
+const EMBEDDING_MODEL: &str = "replicate/all-mpnet-base-v2";
+
+#[derive(Clone)]
+pub struct ContextChunk {
+    pub path: PathBuf,
+    pub line_range: (usize, usize),
+    pub content: String,
+    pub vector: Vec<f32>,
+}
+
+impl ContextChunk {
+    fn format_range(&self) -> String {
+        format!(
+            "{}:{}-{}",
+            self.path.display(),
+            self.line_range.0,
+            self.line_range.1
+        )
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct ContextStore {
+    pub chunks: Vec<ContextChunk>,
+    // Embeddings already computed for a file, keyed by a hash of its
+    // content, so re-attaching an unchanged file doesn't re-embed it.
+    cache: HashMap<u64, Vec<ContextChunk>>,
+}
+
+impl ContextStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Walks `path` (a file or a directory) and attaches every file found to
+    // the store, embedding any chunk whose file content hasn't been seen
+    // before.
+    pub async fn attach(&mut self, path: &Path) -> Result<()> {
+        for entry in WalkDir::new(path)
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file())
+        {
+            self.attach_file(entry.path()).await?;
+        }
+        Ok(())
+    }
+
+    async fn attach_file(&mut self, path: &Path) -> Result<()> {
+        // `tokio::fs::read_to_string` runs the blocking syscall on a
+        // blocking-pool thread instead of the async executor, so one large
+        // file doesn't stall every other in-flight task.
+        let content = match tokio::fs::read_to_string(path).await {
+            Ok(content) => content,
+            // Binary/non-UTF8 files (images, lockfiles, build artifacts)
+            // aren't meaningful context — skip them instead of aborting the
+            // whole walk over one unreadable file.
+            Err(_) => return Ok(()),
+        };
+        let hash = content_hash(&content);
+
+        if let Some(cached) = self.cache.get(&hash) {
+            self.chunks.extend(cached.clone());
+            return Ok(());
+        }
+
+        // Embed every chunk concurrently instead of awaiting one Replicate
+        // round-trip at a time — a file with a dozen chunks no longer pays a
+        // dozen sequential network round-trips.
+        let embedded: Vec<ContextChunk> = try_join_all(
+            chunk_lines(&content, CHUNK_LINES, CHUNK_OVERLAP)
+                .into_iter()
+                .map(|(line_range, chunk_content)| {
+                    let path = path.to_path_buf();
+                    async move {
+                        let vector = embed(&chunk_content).await?;
+                        Ok::<_, anyhow::Error>(ContextChunk {
+                            path,
+                            line_range,
+                            content: chunk_content,
+                            vector,
+                        })
+                    }
+                }),
+        )
+        .await?;
+
+        self.chunks.extend(embedded.clone());
+        self.cache.insert(hash, embedded);
+        Ok(())
+    }
+
+    // Ranks stored chunks by cosine similarity to `query` and greedily takes
+    // them in rank order, skipping any chunk that would push the total past
+    // `char_budget`.
+    pub async fn rank(&self, query: &str, char_budget: usize) -> Result<Vec<&ContextChunk>> {
+        let query_vector = embed(query).await?;
+        Ok(self.rank_with_vector(&query_vector, char_budget))
+    }
+
+    // Same ranking as `rank`, but against an already-embedded query vector.
+    // Lets a caller embed a query once and reuse it across several calls
+    // (e.g. fanning the same prompt out to multiple models) instead of
+    // re-embedding on every call.
+    pub fn rank_with_vector(&self, query_vector: &[f32], char_budget: usize) -> Vec<&ContextChunk> {
+        let mut scored: Vec<(&ContextChunk, f32)> = self
+            .chunks
+            .iter()
+            .map(|chunk| (chunk, cosine_similarity(&chunk.vector, query_vector)))
+            .collect();
+        // `total_cmp` instead of `partial_cmp().unwrap()` — a malformed
+        // embedding vector (e.g. one holding NaN or infinite values) can make
+        // `cosine_similarity` return `NaN`,
+        // which would otherwise panic the sort.
+        scored.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+        let mut taken = Vec::new();
+        let mut used = 0;
+        for (chunk, _) in scored {
+            if used + chunk.content.len() > char_budget {
+                continue;
+            }
+            used += chunk.content.len();
+            taken.push(chunk);
+        }
+        taken
+    }
+}
+
+// Embeds a single piece of text against the configured embedding model.
+// Exposed so callers that need to rank the same query against several
+// things can embed it once and reuse the vector via `rank_with_vector`.
+pub async fn embed_query(text: &str) -> Result<Vec<f32>> {
+    embed(text).await
+}
+
+// Formats the top-ranked chunks as a single synthesized `Role::System`
+// message, `path:line_range` followed by the snippet, so it can be prepended
+// to the prompt alongside the user's message.
+pub fn to_system_message(chunks: &[&ContextChunk]) -> Option<Message> {
+    if chunks.is_empty() {
+        return None;
+    }
+
+    let content = chunks
+        .iter()
+        .map(|chunk| format!("{}\n{}", chunk.format_range(), chunk.content))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    Some(Message::new(Role::System, content, None, None))
+}
+
+fn chunk_lines(content: &str, window: usize, overlap: usize) -> Vec<((usize, usize), String)> {
+    let lines: Vec<&str> = content.lines().collect();
+    if lines.is_empty() {
+        return Vec::new();
+    }
+
+    let stride = window - overlap;
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < lines.len() {
+        let end = (start + window).min(lines.len());
+        chunks.push(((start + 1, end), lines[start..end].join("\n")));
+        if end == lines.len() {
+            break;
+        }
+        start += stride;
+    }
+    chunks
+}
+
+fn content_hash(content: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
+}
+
+async fn embed(text: &str) -> Result<Vec<f32>> {
+    let prediction = Prediction::create(EMBEDDING_MODEL, serde_json::json!({ "text": text }))
+        .await?
+        .wait()
+        .await?;
+    let vector: Vec<f32> = serde_json::from_value(prediction.output)?;
+    Ok(vector)
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b.iter()).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a * norm_b)
+    }
+}
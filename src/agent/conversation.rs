@@ -1,18 +1,114 @@
-use super::message::Message;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::message::{Message, Role};
 use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
-#[derive(Default)]
+// Where saved conversations live, and the schema version written alongside
+// them so a future format change can still read today's files.
+pub const CONVERSATION_DIR: &str = "conversations";
+const CONVERSATION_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct Conversation {
+    pub id: Uuid,
     pub messages: Vec<Message>,
     pub selected_message: Option<usize>,
+    // Tails discarded by `truncate_after`, most recent last. Nothing in
+    // `Conversation` moves them back into `messages`.
+    // Transient: not worth persisting across a reload.
+    #[serde(skip)]
+    pub alternatives: Vec<Vec<Message>>,
+    // Maps an in-flight completion stream to the index of the placeholder
+    // assistant message it updates, so fanning the same prompt out to
+    // several models can update each one's message independently instead of
+    // always stomping on `messages.last()`. Transient: never persisted.
+    #[serde(skip)]
+    streams: HashMap<Uuid, usize>,
+}
+
+// On-disk envelope around a `Conversation`, versioned so a future format
+// change can still tell which shape it's reading.
+#[derive(Serialize, Deserialize)]
+struct ConversationFile {
+    version: u32,
+    updated_at: i64,
+    conversation: Conversation,
 }
 
 impl Conversation {
     pub fn new(messages: Vec<Message>) -> Self {
         Conversation {
+            id: Uuid::new_v4(),
             messages,
             selected_message: None,
+            alternatives: Vec::new(),
+            streams: HashMap::new(),
+        }
+    }
+
+    // Writes this conversation to `CONVERSATION_DIR/<id>.json`, assigning a
+    // fresh id first if this conversation has never been saved before.
+    pub fn save(&mut self) -> anyhow::Result<()> {
+        if self.id.is_nil() {
+            self.id = Uuid::new_v4();
+        }
+
+        fs::create_dir_all(CONVERSATION_DIR)?;
+        let file = ConversationFile {
+            version: CONVERSATION_SCHEMA_VERSION,
+            updated_at: unix_now(),
+            conversation: self.clone(),
+        };
+        let path = PathBuf::from(CONVERSATION_DIR).join(format!("{}.json", self.id));
+        fs::write(path, serde_json::to_vec_pretty(&file)?)?;
+        Ok(())
+    }
+
+    // Deserializes a previously saved conversation back off disk.
+    pub fn load(path: &Path) -> anyhow::Result<Conversation> {
+        let data = fs::read_to_string(path)?;
+        let file: ConversationFile = serde_json::from_str(&data)?;
+        Ok(file.conversation)
+    }
+
+    // Drops every message after `index` and pushes the discarded tail onto
+    // `alternatives`; no method here restores it.
+    pub fn truncate_after(&mut self, index: usize) {
+        if index + 1 < self.messages.len() {
+            let discarded = self.messages.split_off(index + 1);
+            self.alternatives.push(discarded);
         }
+        self.streams.clear();
+        self.select_last_message();
+    }
+
+    // Adds a placeholder assistant message and remembers its index under
+    // `stream_id`, so later `update_stream`/`end_stream` calls for the same
+    // id know which message to touch even while other streams are in
+    // flight.
+    pub fn begin_stream(&mut self, stream_id: Uuid, message: Message) {
+        self.messages.push(message);
+        self.streams.insert(stream_id, self.messages.len() - 1);
+        self.select_last_message();
+    }
+
+    // Replaces the message belonging to `stream_id` in place, leaving every
+    // other in-flight stream's message untouched.
+    pub fn update_stream(&mut self, stream_id: Uuid, message: Message) {
+        if let Some(index) = self.streams.get(&stream_id) {
+            if let Some(existing) = self.messages.get_mut(*index) {
+                *existing = message;
+            }
+        }
+    }
+
+    pub fn end_stream(&mut self, stream_id: Uuid) {
+        self.streams.remove(&stream_id);
     }
 
     pub fn add_message(&mut self, message: Message) {
@@ -23,6 +119,7 @@ impl Conversation {
     pub fn delete_selected_message(&mut self) {
         if let Some(selected_id) = self.selected_message {
             self.messages.remove(selected_id);
+            self.streams.clear();
             self.select_prev_message();
         }
     }
@@ -75,3 +172,99 @@ impl Conversation {
         }
     }
 }
+
+// Summary of a conversation saved under `CONVERSATION_DIR`, cheap enough to
+// keep a list of in memory without loading every message back in.
+#[derive(Debug, Clone)]
+pub struct ConversationEntry {
+    pub id: Uuid,
+    pub path: PathBuf,
+    pub title: String,
+    pub updated_at: i64,
+}
+
+#[derive(Default)]
+pub struct ConversationManager {
+    entries: Vec<ConversationEntry>,
+    pub selected_conversation: usize,
+}
+
+impl ConversationManager {
+    pub fn new() -> Self {
+        let mut manager = Self::default();
+        manager.refresh();
+        manager
+    }
+
+    // Rescans `CONVERSATION_DIR` and rebuilds the entry list from whatever
+    // is saved on disk, most recently updated first.
+    pub fn refresh(&mut self) {
+        let mut entries: Vec<ConversationEntry> = fs::read_dir(CONVERSATION_DIR)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().extension().map(|ext| ext == "json").unwrap_or(false))
+            .filter_map(|entry| load_entry_summary(&entry.path()))
+            .collect();
+        entries.sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
+        self.entries = entries;
+        self.selected_conversation = self.selected_conversation.min(self.entries.len().saturating_sub(1));
+    }
+
+    pub fn list_conversations(&self) -> &[ConversationEntry] {
+        &self.entries
+    }
+
+    pub fn add_conversation(&mut self, mut conversation: Conversation) {
+        conversation.save().ok();
+        self.refresh();
+    }
+
+    pub fn select_prev_conversation(&mut self) {
+        self.selected_conversation = self.selected_conversation.saturating_sub(1);
+    }
+
+    pub fn select_next_conversation(&mut self) {
+        if self.selected_conversation + 1 < self.entries.len() {
+            self.selected_conversation += 1;
+        }
+    }
+
+    // Loads the selected entry's file back into an active `Conversation`.
+    pub fn activate_selected_conversation(&self) -> anyhow::Result<Conversation> {
+        let entry = self
+            .entries
+            .get(self.selected_conversation)
+            .ok_or_else(|| anyhow!("no conversation selected"))?;
+        Conversation::load(&entry.path)
+    }
+}
+
+fn load_entry_summary(path: &Path) -> Option<ConversationEntry> {
+    let data = fs::read_to_string(path).ok()?;
+    let file: ConversationFile = serde_json::from_str(&data).ok()?;
+
+    let title = file
+        .conversation
+        .messages
+        .iter()
+        .find(|message| message.role == Role::User)
+        .and_then(|message| message.content.lines().next())
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_string())
+        .unwrap_or_else(|| "New Conversation".to_string());
+
+    Some(ConversationEntry {
+        id: file.conversation.id,
+        path: path.to_path_buf(),
+        title,
+        updated_at: file.updated_at,
+    })
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or(0)
+}
@@ -0,0 +1,66 @@
+use replicate_rs::predictions::PredictionStatus;
+use serde::{Deserialize, Serialize};
+
+use super::completion::CompletionModel;
+use super::tokens::TokenCounter;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Message {
+    pub role: Role,
+    pub content: String,
+    // In-flight prediction state; meaningless once reloaded from disk.
+    #[serde(skip)]
+    pub status: Option<PredictionStatus>,
+    // `CompletionModel` doesn't implement Serialize/Deserialize yet, so a
+    // reloaded assistant message doesn't remember which model produced it.
+    #[serde(skip)]
+    pub model: Option<CompletionModel>,
+    pub token_count: usize,
+}
+
+impl Message {
+    // Computes and caches the token count up front so `Viewer::draw` doesn't
+    // re-tokenize the whole conversation on every frame.
+    pub fn new(
+        role: Role,
+        content: String,
+        model: Option<CompletionModel>,
+        status: Option<PredictionStatus>,
+    ) -> Self {
+        let token_count = TokenCounter::count(&content);
+        Self {
+            role,
+            content,
+            status,
+            model,
+            token_count,
+        }
+    }
+
+    // Like `new`, but takes an already-known token count instead of
+    // re-tokenizing `content`. Streaming callers that already track a
+    // running token total as tokens arrive should use this to avoid
+    // re-tokenizing the whole (ever-growing) message on every update.
+    pub fn with_token_count(
+        role: Role,
+        content: String,
+        model: Option<CompletionModel>,
+        status: Option<PredictionStatus>,
+        token_count: usize,
+    ) -> Self {
+        Self {
+            role,
+            content,
+            status,
+            model,
+            token_count,
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::sync::OnceLock;
+
+use tiktoken_rs::{cl100k_base, CoreBPE};
+
+use super::completion::CompletionModel;
+use super::message::{Message, Role};
+
+// Fallback for a model this table doesn't recognize yet.
+const DEFAULT_CONTEXT_WINDOW: usize = 8_192;
+
+fn encoder() -> &'static CoreBPE {
+    static ENCODER: OnceLock<CoreBPE> = OnceLock::new();
+    ENCODER.get_or_init(|| cl100k_base().expect("failed to load tokenizer"))
+}
+
+// Context window, in tokens, for each Replicate Llama-family chat model
+// llmit supports. `CompletionModel` doesn't expose this as typed metadata
+// yet (agent::completion isn't in this tree), so this matches on its Debug
+// name as a stopgap — move this onto `CompletionModel` itself once it can
+// carry per-variant metadata.
+fn context_window_for(model: &CompletionModel) -> usize {
+    let name = format!("{model:?}");
+    if name.contains("70b") || name.contains("70B") {
+        8_192
+    } else if name.contains("405b") || name.contains("405B") {
+        128_000
+    } else if name.contains("8b") || name.contains("8B") {
+        8_192
+    } else {
+        DEFAULT_CONTEXT_WINDOW
+    }
+}
+
+// Tracks how many tokens a conversation is using against a model's context
+// window, and trims the oldest messages once it no longer fits.
+pub struct TokenCounter {
+    context_limit: usize,
+}
+
+impl TokenCounter {
+    pub fn for_model(model: &CompletionModel) -> Self {
+        Self {
+            context_limit: context_window_for(model),
+        }
+    }
+
+    pub fn context_limit(&self) -> usize {
+        self.context_limit
+    }
+
+    // Uses OpenAI's `cl100k_base` BPE as an approximation — close enough for
+    // trimming/display purposes, though it won't match a Llama model's own
+    // tokenizer exactly. Swap for a per-model BPE once one's available.
+    pub fn count(content: &str) -> usize {
+        encoder().encode_with_special_tokens(content).len()
+    }
+
+    pub fn total(messages: &[Message]) -> usize {
+        messages.iter().map(|message| message.token_count).sum()
+    }
+
+    // Drops the oldest non-system messages, oldest first, until the
+    // remaining conversation fits under this counter's context limit.
+    pub fn trim_to_fit(&self, messages: &mut Vec<Message>) {
+        while Self::total(messages) > self.context_limit {
+            let drop_index = messages.iter().position(|message| message.role != Role::System);
+            match drop_index {
+                Some(index) => {
+                    messages.remove(index);
+                }
+                None => break,
+            }
+        }
+    }
+}
@@ -4,7 +4,6 @@ use ratatui::{prelude::*, widgets::*};
 use replicate_rs::predictions::PredictionStatus;
 use std::collections::HashMap;
 use std::fmt;
-use std::path::PathBuf;
 use std::str::from_utf8;
 use std::time::Instant;
 use strum::IntoEnumIterator; // 0.17.1
@@ -27,11 +26,6 @@ use async_channel::Sender;
 
 use crate::config::{Config, KeyBindings};
 
-#[derive(Default)]
-pub struct ConversationMeta {
-    path: PathBuf,
-}
-
 #[derive(Default)]
 pub struct ConversationSelector {
     command_tx: Option<Sender<Action>>,
@@ -59,7 +53,9 @@ impl Component for ConversationSelector {
                 self.manager.select_next_conversation();
             }
             Action::LoadSelectedConversation => {
-                self.manager.activate_selected_conversation();
+                if let Ok(conversation) = self.manager.activate_selected_conversation() {
+                    return Ok(Some(Action::LoadConversation(conversation)));
+                }
             }
             Action::AddConversationToManager(convo) => {
                 self.manager.add_conversation(convo);
@@ -71,8 +67,12 @@ impl Component for ConversationSelector {
 
     fn draw(&mut self, f: &mut Frame<'_>, rect: Rect) -> Result<()> {
         let mut items = Vec::new();
-        for id in self.manager.list_conversations() {
-            items.push(ListItem::new(id.to_string()));
+        for entry in self.manager.list_conversations() {
+            items.push(ListItem::new(format!(
+                "{}  ({})",
+                entry.title,
+                format_timestamp(entry.updated_at)
+            )));
         }
 
         let paragraph = List::new(items)
@@ -97,3 +97,24 @@ impl Component for ConversationSelector {
         Ok(())
     }
 }
+
+// Formats a unix timestamp as "YYYY-MM-DD HH:MM" with no timezone database
+// dependency, using Howard Hinnant's civil-from-days conversion.
+fn format_timestamp(unix_seconds: i64) -> String {
+    let days = unix_seconds.div_euclid(86_400);
+    let time_of_day = unix_seconds.rem_euclid(86_400);
+    let (hour, minute) = (time_of_day / 3600, (time_of_day % 3600) / 60);
+
+    let z = days + 719_468;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let doe = (z - era * 146_097) as u64;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let y = yoe as i64 + era * 400;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = if month <= 2 { y + 1 } else { y };
+
+    format!("{year:04}-{month:02}-{day:02} {hour:02}:{minute:02}")
+}
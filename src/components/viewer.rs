@@ -1,19 +1,25 @@
 use futures::StreamExt;
+use pulldown_cmark::{Event, HeadingLevel, Parser, Tag, TagEnd};
 use std::fmt;
 use std::str::from_utf8;
+use std::sync::Arc;
 use std::time::Instant;
 use textwrap::core::Word;
 use textwrap::wrap_algorithms::{wrap_optimal_fit, Penalties};
 use textwrap::WordSeparator;
+use tokio::sync::Mutex;
 
 use color_eyre::eyre::Result;
 use ratatui::{prelude::*, widgets::*};
 use replicate_rs::predictions::PredictionStatus;
+use uuid::Uuid;
 
 use super::Component;
 use crate::agent::completion::stream_completion;
+use crate::agent::context::{self, ContextStore};
 use crate::agent::conversation::Conversation;
 use crate::agent::message::{Message, Role};
+use crate::agent::tokens::TokenCounter;
 use crate::mode::Mode;
 use crate::styles::{
     ACTIVE_COLOR, ASSISTANT_COLOR, FOCUSED_COLOR, SYSTEM_COLOR, UNFOCUSED_COLOR, USER_COLOR,
@@ -31,14 +37,33 @@ enum ViewerState {
     Unfocused,
 }
 
-#[derive(Default)]
+// Upper bound on how many characters of retrieved context get prepended to
+// a prompt, so a large attached codebase doesn't crowd out the conversation.
+const CONTEXT_CHAR_BUDGET: usize = 4_000;
+
 pub struct Viewer {
     command_tx: Option<Sender<Action>>,
     config: Config,
+    // Shared so a spawned `AttachContext` task can populate it and have the
+    // result visible to the next `SendMessage` call. `RegenerateFromSelected`
+    // does not read it.
+    context: Arc<Mutex<ContextStore>>,
     conversation: Conversation,
     state: ViewerState,
 }
 
+impl Default for Viewer {
+    fn default() -> Self {
+        Self {
+            command_tx: None,
+            config: Config::default(),
+            context: Arc::new(Mutex::new(ContextStore::default())),
+            conversation: Conversation::default(),
+            state: ViewerState::default(),
+        }
+    }
+}
+
 impl Viewer {
     pub fn new(focused: bool) -> Self {
         let state = if focused {
@@ -67,12 +92,22 @@ impl Component for Viewer {
 
     fn update(&mut self, action: Action) -> Result<Option<Action>> {
         match action {
-            Action::ReceiveMessage(message) => {
-                self.conversation.add_message(message);
+            Action::ReceiveMessage(stream_id, message) => {
+                match message.role {
+                    Role::Assistant => self.conversation.begin_stream(stream_id, message),
+                    _ => self.conversation.add_message(message),
+                }
+                self.conversation.save().ok();
+            }
+            Action::StreamMessage(stream_id, message) => {
+                self.conversation.update_stream(stream_id, message);
+            }
+            Action::EndStream(stream_id) => {
+                self.conversation.end_stream(stream_id);
+                self.conversation.save().ok();
             }
-            Action::StreamMessage(message) => {
-                // Simply replace the last message
-                self.conversation.replace_last_message(message);
+            Action::LoadConversation(conversation) => {
+                self.conversation = conversation;
             }
 
             Action::SwitchMode(mode) => match mode {
@@ -103,65 +138,96 @@ impl Component for Viewer {
             }
             Action::DeleteSelectedMessage => {
                 self.conversation.delete_selected_message();
+                self.conversation.save().ok();
+            }
+            Action::AttachContext(path) => {
+                let context = self.context.clone();
+                tokio::spawn(async move {
+                    context.lock().await.attach(&path).await.ok();
+                });
             }
-            Action::SendMessage(message) => {
+            Action::SendMessage(message, models) => {
                 // Lets clean this up at some point
                 // I don't think this cloning is ideal
-                let model = message.model.clone();
                 let action_tx = self.command_tx.clone().unwrap();
-                let mut messages = self.conversation.messages.clone();
+                let history = self.conversation.messages.clone();
+                let context = self.context.clone();
                 tokio::spawn(async move {
                     action_tx
-                        .send(Action::ReceiveMessage(message.clone()))
+                        .send(Action::ReceiveMessage(Uuid::new_v4(), message.clone()))
                         .await
                         .ok();
 
-                    if let Some(model) = model {
-                        let mut content = String::new();
+                    // Embed the prompt once and reuse the vector across every
+                    // selected model instead of re-embedding it per model.
+                    // Skip the embedding call entirely if nothing was
+                    // selected to receive it.
+                    let context_message = if models.is_empty() {
+                        None
+                    } else {
+                        match context::embed_query(&message.content).await.ok() {
+                            Some(vector) => {
+                                let store = context.lock().await;
+                                let chunks = store.rank_with_vector(&vector, CONTEXT_CHAR_BUDGET);
+                                context::to_system_message(&chunks)
+                            }
+                            None => None,
+                        }
+                    };
 
-                        action_tx
-                            .send(Action::ReceiveMessage(Message {
-                                role: Role::Assistant,
-                                content: content.clone(),
-                                status: Some(PredictionStatus::Starting),
-                                model: Some(model.clone()),
-                            }))
-                            .await
-                            .ok();
-                        messages.push(message);
-
-                        let stream = stream_completion(&model, messages).await;
-                        match stream {
-                            Ok((status, mut stream)) => {
-                                while let Some(event) = stream.next().await {
-                                    match event {
-                                        Ok(event) => {
-                                            if event.event == "done" {
-                                                break;
-                                            }
-                                            content.push_str(event.data.as_str());
-                                            action_tx
-                                                .send(Action::StreamMessage(Message {
-                                                    role: Role::Assistant,
-                                                    content: content.clone(),
-                                                    status: None,
-                                                    model: Some(model.clone()),
-                                                }))
-                                                .await
-                                                .ok();
-                                        }
-                                        Err(err) => {
-                                            panic!("{:?}", err);
-                                        }
-                                    }
-                                }
+                    // One streaming task per selected model, each addressed
+                    // by its own stream id so they can update independently
+                    // instead of racing over `messages.last()`.
+                    for model in models {
+                        let action_tx = action_tx.clone();
+                        let mut messages = history.clone();
+                        let context_message = context_message.clone();
+                        let mut outgoing = message.clone();
+                        tokio::spawn(async move {
+                            if let Some(context_message) = context_message {
+                                messages.push(context_message);
                             }
-                            Err(err) => {
-                                panic!("{err}");
+
+                            outgoing.model = Some(model.clone());
+                            messages.push(outgoing);
+                            stream_assistant_response(&action_tx, Uuid::new_v4(), model, messages)
+                                .await;
+                        });
+                    }
+                });
+            }
+            Action::RegenerateFromSelected => {
+                if let Some(selected) = self.conversation.selected_message {
+                    // Regenerating only makes sense from the prompt side of
+                    // the conversation — picking an assistant reply would
+                    // regenerate an assistant response after another one.
+                    let is_prompt = self
+                        .conversation
+                        .messages
+                        .get(selected)
+                        .map(|message| message.role != Role::Assistant)
+                        .unwrap_or(false);
+
+                    if is_prompt {
+                        self.conversation.truncate_after(selected);
+
+                        if let Ok(message) = self.conversation.get_selected_message() {
+                            if let Some(model) = message.model.clone() {
+                                let action_tx = self.command_tx.clone().unwrap();
+                                let messages = self.conversation.messages.clone();
+                                tokio::spawn(async move {
+                                    stream_assistant_response(
+                                        &action_tx,
+                                        Uuid::new_v4(),
+                                        model,
+                                        messages,
+                                    )
+                                    .await;
+                                });
                             }
                         }
                     }
-                });
+                }
             }
             _ => {}
         }
@@ -203,22 +269,7 @@ impl Component for Viewer {
                 }
             }
 
-            for line in message.content.split("\n") {
-                let words = WordSeparator::AsciiSpace
-                    .find_words(line)
-                    .collect::<Vec<_>>();
-                let subs = lines_to_strings(
-                    wrap_optimal_fit(&words, &[rect.width as f64 - 2.0], &Penalties::new())
-                        .unwrap(),
-                );
-
-                for sub in subs {
-                    message_lines.push(Line::from(vec![Span::styled(
-                        sub,
-                        Style::default().fg(Color::White),
-                    )]));
-                }
-            }
+            message_lines.extend(render_markdown(&message.content, rect.width - 2));
 
             let mut break_line = String::new();
             for _ in 0..(rect.width - 2) {
@@ -233,10 +284,11 @@ impl Component for Viewer {
         }
 
         let vertical_scroll = 0;
+        let title = conversation_title(&self.conversation);
         let list = List::new(message_items.clone())
             .block(
                 Block::default()
-                    .title(" Conversation ")
+                    .title(title)
                     .borders(Borders::ALL)
                     .border_type(BorderType::Thick)
                     .style(Style::default().fg(match self.state {
@@ -273,6 +325,124 @@ impl Component for Viewer {
         Ok(())
     }
 }
+// Builds the block title, e.g. " Conversation — 3,142 / 8,192 tok ", using
+// the context window of the model on the most recent message that has one.
+// Falls back to a plain title when no message has a model.
+fn conversation_title(conversation: &Conversation) -> String {
+    let total = TokenCounter::total(&conversation.messages);
+    let context_limit = conversation
+        .messages
+        .iter()
+        .rev()
+        .find_map(|message| message.model.as_ref())
+        .map(|model| TokenCounter::for_model(model).context_limit());
+
+    match context_limit {
+        Some(limit) => format!(
+            " Conversation — {} / {} tok ",
+            format_thousands(total),
+            format_thousands(limit)
+        ),
+        None => " Conversation ".to_string(),
+    }
+}
+
+fn format_thousands(n: usize) -> String {
+    let digits = n.to_string();
+    let mut grouped = String::new();
+    for (index, digit) in digits.chars().rev().enumerate() {
+        if index > 0 && index % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped.chars().rev().collect()
+}
+
+// Streams a completion for `messages` against `model`, publishing a starting
+// placeholder followed by incremental `StreamMessage` updates as tokens
+// arrive. Shared by `Action::SendMessage` and `Action::RegenerateFromSelected`
+// so both paths drive the exact same streaming behavior.
+async fn stream_assistant_response(
+    action_tx: &Sender<Action>,
+    stream_id: Uuid,
+    model: CompletionModel,
+    messages: Vec<Message>,
+) {
+    let mut content = String::new();
+    let mut messages = messages;
+    TokenCounter::for_model(&model).trim_to_fit(&mut messages);
+
+    action_tx
+        .send(Action::ReceiveMessage(
+            stream_id,
+            Message::new(
+                Role::Assistant,
+                content.clone(),
+                Some(model.clone()),
+                Some(PredictionStatus::Starting),
+            ),
+        ))
+        .await
+        .ok();
+
+    // Sum of each chunk's own token count, kept only as a cheap interim
+    // display estimate while the stream is in flight — it double-counts
+    // word-pieces that get split across chunk boundaries and doesn't match
+    // `TokenCounter::count`'s segmentation of the full string, so it's never
+    // treated as authoritative.
+    let mut running_estimate = 0usize;
+
+    let stream = stream_completion(&model, messages).await;
+    match stream {
+        Ok((status, mut stream)) => {
+            while let Some(event) = stream.next().await {
+                match event {
+                    Ok(event) => {
+                        if event.event == "done" {
+                            break;
+                        }
+                        content.push_str(event.data.as_str());
+                        running_estimate += TokenCounter::count(event.data.as_str());
+                        action_tx
+                            .send(Action::StreamMessage(
+                                stream_id,
+                                Message::with_token_count(
+                                    Role::Assistant,
+                                    content.clone(),
+                                    Some(model.clone()),
+                                    None,
+                                    running_estimate,
+                                ),
+                            ))
+                            .await
+                            .ok();
+                    }
+                    Err(err) => {
+                        panic!("{:?}", err);
+                    }
+                }
+            }
+        }
+        Err(err) => {
+            panic!("{err}");
+        }
+    }
+
+    // Reconcile once against the full response before it's persisted and
+    // fed into `trim_to_fit` — the running estimate above is only ever an
+    // interim display value, not the count we store.
+    action_tx
+        .send(Action::StreamMessage(
+            stream_id,
+            Message::new(Role::Assistant, content, Some(model), None),
+        ))
+        .await
+        .ok();
+
+    action_tx.send(Action::EndStream(stream_id)).await.ok();
+}
+
 //
 // Helper to convert wrapped lines to a Vec<String>.
 fn lines_to_strings(lines: Vec<&[Word<'_>]>) -> Vec<String> {
@@ -286,3 +456,183 @@ fn lines_to_strings(lines: Vec<&[Word<'_>]>) -> Vec<String> {
         })
         .collect::<Vec<_>>()
 }
+
+// Wraps a single prose string to `width` columns using the existing
+// optimal-fit algorithm, returning one String per wrapped row.
+fn wrap_prose(text: &str, width: u16) -> Vec<String> {
+    let words = WordSeparator::AsciiSpace.find_words(text).collect::<Vec<_>>();
+    lines_to_strings(wrap_optimal_fit(&words, &[width as f64], &Penalties::new()).unwrap())
+}
+
+// Delimits an inline code span within a block's plain-text buffer so it
+// survives `wrap_prose`'s wrapping and can be pulled back out by
+// `inline_code_spans`. Chosen because it can't appear in markdown source.
+const INLINE_CODE_MARKER: char = '\u{0}';
+
+// Splits a wrapped line back into alternating prose/inline-code `Span`s using
+// the `INLINE_CODE_MARKER` delimiters `render_markdown` wrapped inline code
+// in before wrapping.
+fn inline_code_spans(text: &str, prose_style: Style, code_style: Style) -> Vec<Span<'static>> {
+    text.split(INLINE_CODE_MARKER)
+        .enumerate()
+        .filter(|(_, segment)| !segment.is_empty())
+        .map(|(i, segment)| {
+            let style = if i % 2 == 1 { code_style } else { prose_style };
+            Span::styled(segment.to_string(), style)
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum MarkdownBlock {
+    Paragraph,
+    Heading(HeadingLevel),
+    BlockQuote,
+    Item,
+}
+
+// Renders a message's markdown content into styled `Line`s ready to wrap into
+// the conversation list. Fenced code blocks are emitted verbatim (indentation
+// preserved, no optimal-fit wrapping) while everything else is treated as
+// prose and passed through `wrap_prose`.
+fn render_markdown(content: &str, width: u16) -> Vec<Line<'static>> {
+    let code_style = Style::default().bg(Color::Rgb(30, 30, 30)).fg(Color::Gray);
+    let quote_style = Style::default().fg(Color::DarkGray).add_modifier(Modifier::ITALIC);
+    let heading_style = Style::default().fg(Color::White).bold();
+    let prose_style = Style::default().fg(Color::White);
+
+    let mut lines = Vec::new();
+    let mut block: Option<MarkdownBlock> = None;
+    let mut buffer = String::new();
+    let mut in_code_block = false;
+    let mut list_item_index: Vec<Option<u64>> = Vec::new();
+
+    let flush_block = |lines: &mut Vec<Line<'static>>, block: MarkdownBlock, buffer: &mut String| {
+        if buffer.is_empty() {
+            return;
+        }
+        let (prefix, style) = match block {
+            MarkdownBlock::Paragraph => (String::new(), prose_style),
+            MarkdownBlock::Heading(_) => (String::new(), heading_style),
+            MarkdownBlock::BlockQuote => ("> ".to_string(), quote_style),
+            MarkdownBlock::Item => (String::new(), prose_style),
+        };
+        let wrap_width = (width as usize).saturating_sub(prefix.len()).max(1) as u16;
+        // An inline code span can be wrapped across rows; carry whether the
+        // previous row ended inside one so this row starts in code style.
+        let mut in_code = false;
+        for wrapped in wrap_prose(buffer.trim(), wrap_width) {
+            let mut spans = Vec::new();
+            if !prefix.is_empty() {
+                spans.push(Span::styled(prefix.clone(), style));
+            }
+            let row = if in_code {
+                format!("{INLINE_CODE_MARKER}{wrapped}")
+            } else {
+                wrapped.clone()
+            };
+            spans.extend(inline_code_spans(&row, style, code_style));
+            if wrapped.matches(INLINE_CODE_MARKER).count() % 2 == 1 {
+                in_code = !in_code;
+            }
+            lines.push(Line::from(spans));
+        }
+        buffer.clear();
+    };
+
+    for event in Parser::new(content) {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => {
+                if let Some(b) = block.take() {
+                    flush_block(&mut lines, b, &mut buffer);
+                }
+                in_code_block = true;
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                in_code_block = false;
+                lines.push(Line::from(vec![Span::styled("", Style::default())]));
+            }
+            Event::Start(Tag::Heading { level, .. }) => {
+                block = Some(MarkdownBlock::Heading(level));
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                if let Some(b) = block.take() {
+                    flush_block(&mut lines, b, &mut buffer);
+                }
+            }
+            Event::Start(Tag::BlockQuote(_)) => {
+                block = Some(MarkdownBlock::BlockQuote);
+            }
+            Event::End(TagEnd::BlockQuote(_)) => {
+                if let Some(b) = block.take() {
+                    flush_block(&mut lines, b, &mut buffer);
+                }
+            }
+            Event::Start(Tag::List(start)) => {
+                list_item_index.push(start);
+            }
+            Event::End(TagEnd::List(_)) => {
+                list_item_index.pop();
+            }
+            Event::Start(Tag::Item) => {
+                block = Some(MarkdownBlock::Item);
+                let prefix = match list_item_index.last_mut() {
+                    Some(Some(n)) => {
+                        let marker = format!("{n}. ");
+                        *n += 1;
+                        marker
+                    }
+                    _ => "- ".to_string(),
+                };
+                buffer.push_str(&prefix);
+            }
+            Event::End(TagEnd::Item) => {
+                if let Some(b) = block.take() {
+                    flush_block(&mut lines, b, &mut buffer);
+                }
+            }
+            Event::Start(Tag::Paragraph) => {
+                if block.is_none() {
+                    block = Some(MarkdownBlock::Paragraph);
+                }
+            }
+            Event::End(TagEnd::Paragraph) => {
+                if let Some(b) = block.take() {
+                    flush_block(&mut lines, b, &mut buffer);
+                }
+            }
+            Event::Text(text) => {
+                if in_code_block {
+                    // `split_terminator` drops only the empty string produced
+                    // by a trailing newline, so genuine blank lines inside
+                    // the code block (e.g. between functions) are kept.
+                    for raw_line in text.split_terminator('\n') {
+                        lines.push(Line::from(vec![Span::styled(
+                            raw_line.to_string(),
+                            code_style,
+                        )]));
+                    }
+                } else {
+                    buffer.push_str(&text);
+                }
+            }
+            // Inline code (`` `like this` ``), as opposed to a fenced code
+            // block. Wrapped in sentinel markers so it survives
+            // `wrap_prose`'s plain-text wrapping and still gets pulled back
+            // out into its own `code_style` span by `inline_code_spans`.
+            Event::Code(text) => {
+                buffer.push(INLINE_CODE_MARKER);
+                buffer.push_str(&text);
+                buffer.push(INLINE_CODE_MARKER);
+            }
+            Event::SoftBreak | Event::HardBreak => buffer.push(' '),
+            _ => {}
+        }
+    }
+
+    if let Some(b) = block.take() {
+        flush_block(&mut lines, b, &mut buffer);
+    }
+
+    lines
+}